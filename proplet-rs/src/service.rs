@@ -4,6 +4,8 @@ use crate::runtime::{Runtime, RuntimeContext, StartConfig};
 use crate::types::*;
 use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
 use anyhow::{Context, Result};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
@@ -11,38 +13,332 @@ use tokio::sync::{mpsc, Mutex};
 use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 
-#[derive(Debug)]
+/// Claims carried by the HS256 token a manager attaches to every
+/// `control/manager/*` command so a proplet can tell a genuine command
+/// from a forged one published on the same channel.
+#[derive(Debug, Serialize, Deserialize)]
+struct CommandClaims {
+    /// Hex-encoded SHA256 of the command's serialized body, binding the
+    /// token to this exact payload.
+    body_hash: String,
+    /// Issued-at, unix seconds.
+    iat: i64,
+    /// Expiry, unix seconds; commands presented after this are rejected.
+    exp: i64,
+}
+
+/// Verifies an HS256 command token against the secret and the raw payload
+/// it was issued for. Split out from [`PropletService::verify_command`] so
+/// the token/hash checks can be exercised without an `MqttMessage` or
+/// `PropletConfig`.
+fn verify_command_token(token: &str, secret: &[u8], payload: &[u8]) -> Result<()> {
+    let decoding_key = DecodingKey::from_secret(secret);
+    let mut validation = Validation::new(jsonwebtoken::Algorithm::HS256);
+    validation.validate_exp = true;
+    validation.leeway = 0;
+    validation.set_required_spec_claims(&["exp", "iat"]);
+
+    let data = decode::<CommandClaims>(token, &decoding_key, &validation)
+        .context("Invalid or expired command auth token")?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("System clock is before the unix epoch")?
+        .as_secs() as i64;
+    if data.claims.iat > now {
+        return Err(anyhow::anyhow!("Command auth token is issued in the future"));
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(payload);
+    let expected_hash = hex::encode(hasher.finalize());
+
+    if data.claims.body_hash != expected_hash {
+        return Err(anyhow::anyhow!("Command auth token does not match payload"));
+    }
+
+    Ok(())
+}
+
+/// A single entry in the [`BinaryCache`], tracking the decrypted bytes plus
+/// when they were last touched so the cache can evict least-recently-used
+/// entries once a configured bound is hit.
+struct BinaryCacheEntry {
+    data: Vec<u8>,
+    last_used: Instant,
+}
+
+/// Bounded, checksum-keyed cache of decrypted WASM binaries.
+///
+/// `request_binary_from_registry` + `wait_for_binary` re-download and
+/// re-decrypt a binary on every `handle_start_command`, even though the
+/// content behind a given `checksum` is immutable. Caching the decrypted
+/// bytes turns a repeated launch of the same workload into an in-memory
+/// hit. Both the number of entries and the total byte budget are bounded so
+/// memory-constrained edge devices can cap usage; whichever limit is hit
+/// first triggers eviction of the least-recently-used entry.
+struct BinaryCache {
+    entries: HashMap<String, BinaryCacheEntry>,
+    max_entries: usize,
+    max_bytes: usize,
+    total_bytes: usize,
+}
+
+impl BinaryCache {
+    fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_entries,
+            max_bytes,
+            total_bytes: 0,
+        }
+    }
+
+    fn get(&mut self, checksum: &str) -> Option<Vec<u8>> {
+        let entry = self.entries.get_mut(checksum)?;
+        entry.last_used = Instant::now();
+        Some(entry.data.clone())
+    }
+
+    fn insert(&mut self, checksum: String, data: Vec<u8>) {
+        if self.max_entries == 0 || self.max_bytes == 0 || data.len() > self.max_bytes {
+            return;
+        }
+
+        if let Some(old) = self.entries.remove(&checksum) {
+            self.total_bytes -= old.data.len();
+        }
+
+        while !self.entries.is_empty()
+            && (self.entries.len() >= self.max_entries
+                || self.total_bytes + data.len() > self.max_bytes)
+        {
+            if let Some(lru_key) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            {
+                if let Some(evicted) = self.entries.remove(&lru_key) {
+                    self.total_bytes -= evicted.data.len();
+                    debug!("Evicted LRU binary cache entry '{}'", lru_key);
+                }
+            } else {
+                break;
+            }
+        }
+
+        self.total_bytes += data.len();
+        self.entries.insert(
+            checksum,
+            BinaryCacheEntry {
+                data,
+                last_used: Instant::now(),
+            },
+        );
+    }
+}
+
+/// Verifies a single chunk against a Merkle root using its authentication
+/// path, so a corrupt or forged chunk is caught the instant it arrives
+/// instead of after every chunk has been collected.
+///
+/// `leaf = SHA256(chunk_data)`; the path is the list of sibling hashes from
+/// the leaf up to the root, one per tree level. At each level the current
+/// index's low bit says whether the running hash is the left or right
+/// child: `0` -> `hash(current || sibling)`, `1` -> `hash(sibling ||
+/// current)`. The final value must equal `root`.
+fn verify_merkle_path(chunk_data: &[u8], chunk_idx: usize, auth_path: &[String], root: &str) -> bool {
+    let mut hasher = Sha256::new();
+    hasher.update(chunk_data);
+    let mut current = hasher.finalize_reset().to_vec();
+
+    let mut index = chunk_idx;
+    for sibling_hex in auth_path {
+        let sibling = match hex::decode(sibling_hex) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+
+        if index % 2 == 0 {
+            hasher.update(&current);
+            hasher.update(&sibling);
+        } else {
+            hasher.update(&sibling);
+            hasher.update(&current);
+        }
+        current = hasher.finalize_reset().to_vec();
+        index /= 2;
+    }
+
+    hex::encode(current) == root
+}
+
+/// Derives a filesystem-safe name for a chunk spill file from an
+/// attacker-controlled `app_name`. `registry/server` carries no
+/// authentication, so `app_name` cannot be trusted as a path component
+/// (it could contain `/`, `..`, or arbitrary bytes) — hashing it sidesteps
+/// path traversal entirely while keeping one stable file per app.
+fn spill_file_name(app_name: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(app_name.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
 struct ChunkAssemblyState {
+    /// Chunks not yet spilled to disk: either the whole transfer (while
+    /// under `chunk_spill_bytes`) or the out-of-order tail that hasn't
+    /// joined the contiguous prefix yet.
     chunks: BTreeMap<usize, Vec<u8>>,
     total_chunks: usize,
     checksum: String,
     created_at: Instant,
+    /// Every chunk index seen so far, flushed to disk or not, so
+    /// completeness and gap detection don't depend on what's still
+    /// resident in `chunks`.
+    received: std::collections::HashSet<usize>,
+    /// Path of the spill file for this assembly, once one has been opened.
+    spill_path: Option<std::path::PathBuf>,
+    spill_file: Option<tokio::fs::File>,
+    /// Bytes already appended to the spill file.
+    flushed_bytes: u64,
+    /// Next index that must arrive to extend the contiguous prefix written
+    /// to the spill file.
+    next_flush_idx: usize,
+    /// Bytes currently held in `chunks`, used to decide when to start
+    /// spilling.
+    buffered_bytes: usize,
+    /// When a chunk index not seen before last arrived (redeliveries do
+    /// not count); used to detect a stalled
+    /// transfer worth sending a NACK for.
+    last_progress: Instant,
 }
 
 impl ChunkAssemblyState {
     fn new(total_chunks: usize, checksum: String) -> Self {
+        let now = Instant::now();
         Self {
             chunks: BTreeMap::new(),
             total_chunks,
             checksum,
-            created_at: Instant::now(),
+            created_at: now,
+            received: std::collections::HashSet::new(),
+            spill_path: None,
+            spill_file: None,
+            flushed_bytes: 0,
+            next_flush_idx: 0,
+            buffered_bytes: 0,
+            last_progress: now,
         }
     }
 
     fn is_complete(&self) -> bool {
-        self.chunks.len() == self.total_chunks
+        self.received.len() == self.total_chunks
     }
 
     fn is_expired(&self, ttl: tokio::time::Duration) -> bool {
         self.created_at.elapsed() > ttl
     }
 
-    fn assemble(&self) -> Vec<u8> {
-        let mut binary = Vec::new();
+    /// Indices in `0..total_chunks` not yet received.
+    fn missing_chunks(&self) -> Vec<usize> {
+        (0..self.total_chunks)
+            .filter(|idx| !self.received.contains(idx))
+            .collect()
+    }
+
+    /// Records a newly-arrived chunk. Below `spill_threshold_bytes` this
+    /// just buffers in memory like before; once the buffer reaches the
+    /// threshold, a spill file is opened and the contiguous prefix of
+    /// chunks is streamed out to it and dropped from memory, bounding
+    /// resident memory while the transfer is in flight. The spilled bytes
+    /// are read back whole by [`Self::assemble`].
+    async fn store_chunk(
+        &mut self,
+        app_name: &str,
+        chunk_idx: usize,
+        data: Vec<u8>,
+        spill_threshold_bytes: usize,
+        spill_dir: &std::path::Path,
+    ) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        if chunk_idx < self.next_flush_idx {
+            debug!(
+                "Ignoring redelivered chunk {} for '{}': already flushed to disk",
+                chunk_idx, app_name
+            );
+            return Ok(());
+        }
+
+        if !self.received.contains(&chunk_idx) {
+            self.last_progress = Instant::now();
+        }
+
+        self.received.insert(chunk_idx);
+        if !self.chunks.contains_key(&chunk_idx) {
+            self.buffered_bytes += data.len();
+        }
+        self.chunks.insert(chunk_idx, data);
+
+        if self.spill_file.is_none() && self.buffered_bytes >= spill_threshold_bytes {
+            let path = spill_dir.join(format!("proplet-{}.chunks", spill_file_name(app_name)));
+            let file = tokio::fs::File::create(&path)
+                .await
+                .context("Failed to create chunk spill file")?;
+            debug!("Spilling chunk assembly for '{}' to {:?}", app_name, path);
+            self.spill_path = Some(path);
+            self.spill_file = Some(file);
+        }
+
+        if let Some(file) = self.spill_file.as_mut() {
+            while let Some(chunk_data) = self.chunks.get(&self.next_flush_idx) {
+                file.write_all(chunk_data)
+                    .await
+                    .context("Failed to spill chunk to disk")?;
+                self.flushed_bytes += chunk_data.len() as u64;
+                self.buffered_bytes -= chunk_data.len();
+                self.chunks.remove(&self.next_flush_idx);
+                self.next_flush_idx += 1;
+            }
+            file.flush().await.context("Failed to flush chunk spill file")?;
+            debug!(
+                "Flushed {} bytes to spill file for '{}' ({} chunk(s) buffered)",
+                self.flushed_bytes,
+                app_name,
+                self.chunks.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Reassembles the encrypted binary by reading the spilled prefix back
+    /// from disk in one pass and appending any chunks still buffered. The
+    /// whole encrypted binary is returned as one `Vec<u8>`; it is not
+    /// streamed to the caller.
+    async fn assemble(&self) -> Result<Vec<u8>> {
+        let mut binary = if let Some(path) = &self.spill_path {
+            tokio::fs::read(path)
+                .await
+                .context("Failed to read spilled chunk file")?
+        } else {
+            Vec::new()
+        };
+
         for chunk_data in self.chunks.values() {
             binary.extend_from_slice(chunk_data);
         }
-        binary
+
+        Ok(binary)
+    }
+
+    async fn cleanup(&self) {
+        if let Some(path) = &self.spill_path {
+            if let Err(e) = tokio::fs::remove_file(path).await {
+                warn!("Failed to remove chunk spill file {:?}: {}", path, e);
+            }
+        }
     }
 }
 
@@ -54,6 +350,7 @@ pub struct PropletService {
     chunk_assembly: Arc<Mutex<HashMap<String, ChunkAssemblyState>>>,
     running_tasks: Arc<Mutex<HashMap<String, TaskState>>>,
     workload_key: Key<Aes256Gcm>,
+    binary_cache: Arc<Mutex<BinaryCache>>,
 }
 
 impl PropletService {
@@ -68,6 +365,7 @@ impl PropletService {
         }
         
         let workload_key = *Key::<Aes256Gcm>::from_slice(&key_bytes);
+        let binary_cache = BinaryCache::new(config.binary_cache_capacity, config.binary_cache_max_bytes);
 
         let service = Self {
             config,
@@ -77,6 +375,7 @@ impl PropletService {
             chunk_assembly: Arc::new(Mutex::new(HashMap::new())),
             running_tasks: Arc::new(Mutex::new(HashMap::new())),
             workload_key,
+            binary_cache: Arc::new(Mutex::new(binary_cache)),
         };
 
         service.start_chunk_expiry_task();
@@ -84,6 +383,28 @@ impl PropletService {
         Ok(service)
     }
 
+    /// Verifies the HS256 command token a manager attaches to a
+    /// `control/manager/start` or `control/manager/stop` message before the
+    /// handler is allowed to act on it. The `workload_key` only protects
+    /// confidentiality of encrypted payloads; this closes the separate gap
+    /// where anyone able to publish on the channel could otherwise start or
+    /// stop arbitrary tasks.
+    ///
+    /// The manager signs a token whose `body_hash` claim is the hex SHA256
+    /// of the raw command payload and attaches it as the `x-proplet-auth`
+    /// MQTT user property. We re-hash the payload we actually received and
+    /// compare, so the token cannot be replayed against a different body;
+    /// `exp` is validated with no leeway to reject stale tokens, and an
+    /// `iat` later than the current time rejects future-dated ones.
+    fn verify_command(&self, msg: &MqttMessage) -> Result<()> {
+        let token = msg
+            .properties
+            .get("x-proplet-auth")
+            .ok_or_else(|| anyhow::anyhow!("Missing command auth token"))?;
+
+        verify_command_token(token, self.config.command_signing_secret.as_bytes(), &msg.payload)
+    }
+
     fn decrypt_payload(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
         let cipher = Aes256Gcm::new(&self.workload_key);
         
@@ -121,9 +442,10 @@ impl PropletService {
                         warn!(
                             "Expired incomplete chunk assembly for '{}': received {}/{} chunks",
                             app_name,
-                            state.chunks.len(),
+                            state.received.len(),
                             state.total_chunks
                         );
+                        state.cleanup().await;
                     }
                 }
             }
@@ -264,6 +586,13 @@ impl PropletService {
     }
 
     async fn handle_start_command(&self, msg: MqttMessage) -> Result<()> {
+        if let Err(e) = self.verify_command(&msg) {
+            error!("Rejected start command: {}", e);
+            self.publish_result("unknown", Vec::new(), Some(e.to_string()))
+                .await?;
+            return Err(e);
+        }
+
         let req: StartRequest = msg.decode().map_err(|e| {
             error!("Failed to decode start request: {}", e);
             if let Ok(payload_str) = String::from_utf8(msg.payload.clone()) {
@@ -312,16 +641,26 @@ impl PropletService {
                 }
             }
         } else if !req.image_url.is_empty() {
+            let cached = if !req.checksum.is_empty() {
+                self.binary_cache.lock().await.get(&req.checksum)
+            } else {
+                None
+            };
 
-            info!("Requesting binary from registry: {}", req.image_url);
-            self.request_binary_from_registry(&req.image_url).await?;
-
-            match self.wait_for_binary(&req.image_url).await {
-                Ok(binary) => binary,
-                Err(e) => {
-                    error!("Failed to get binary for task {}: {}", req.id, e);
-                    self.publish_result(&req.id, Vec::new(), Some(e.to_string())).await?;
-                    return Err(e);
+            if let Some(binary) = cached {
+                info!("Using cached binary for checksum {}", req.checksum);
+                binary
+            } else {
+                info!("Requesting binary from registry: {}", req.image_url);
+                self.request_binary_from_registry(&req.image_url).await?;
+
+                match self.wait_for_binary(&req.image_url).await {
+                    Ok(binary) => binary,
+                    Err(e) => {
+                        error!("Failed to get binary for task {}: {}", req.id, e);
+                        self.publish_result(&req.id, Vec::new(), Some(e.to_string())).await?;
+                        return Err(e);
+                    }
                 }
             }
         } else {
@@ -401,6 +740,13 @@ impl PropletService {
     }
 
     async fn handle_stop_command(&self, msg: MqttMessage) -> Result<()> {
+        if let Err(e) = self.verify_command(&msg) {
+            error!("Rejected stop command: {}", e);
+            self.publish_result("unknown", Vec::new(), Some(e.to_string()))
+                .await?;
+            return Err(e);
+        }
+
         let req: StopRequest = msg.decode()?;
         req.validate()?;
 
@@ -447,12 +793,38 @@ impl PropletService {
              return Err(anyhow::anyhow!("Chunk checksum mismatch"));
         }
 
-        state.chunks.insert(chunk.chunk_idx, chunk.data);
+        if !chunk.auth_path.is_empty() && !state.checksum.is_empty() {
+            if !verify_merkle_path(&chunk.data, chunk.chunk_idx, &chunk.auth_path, &state.checksum) {
+                error!(
+                    "Merkle verification failed for chunk {} of app '{}'; rejecting chunk",
+                    chunk.chunk_idx, chunk.app_name
+                );
+                return Err(anyhow::anyhow!(
+                    "Merkle verification failed for chunk {} of app '{}'",
+                    chunk.chunk_idx,
+                    chunk.app_name
+                ));
+            }
+            debug!(
+                "Merkle path verified for chunk {} of app '{}'",
+                chunk.chunk_idx, chunk.app_name
+            );
+        }
+
+        state
+            .store_chunk(
+                &chunk.app_name,
+                chunk.chunk_idx,
+                chunk.data,
+                self.config.chunk_spill_bytes,
+                &std::env::temp_dir(),
+            )
+            .await?;
         debug!(
             "Stored chunk {} for app '{}' ({}/{} chunks received)",
             chunk.chunk_idx,
             chunk.app_name,
-            state.chunks.len(),
+            state.received.len(),
             state.total_chunks
         );
 
@@ -480,10 +852,47 @@ impl PropletService {
         Ok(())
     }
 
+    /// Publishes a `ChunkNack` listing the indices still missing for
+    /// `app_name` so the registry can resend only those chunks instead of
+    /// the whole transfer.
+    async fn publish_nack(&self, app_name: &str, missing: Vec<usize>) -> Result<()> {
+        #[derive(serde::Serialize)]
+        struct ChunkNack {
+            app_name: String,
+            missing: Vec<usize>,
+        }
+
+        let topic = build_topic(
+            &self.config.domain_id,
+            &self.config.channel_id,
+            "registry/proplet/nack",
+        );
+
+        warn!(
+            "Transfer for '{}' stalled, requesting {} missing chunk(s)",
+            app_name,
+            missing.len()
+        );
+
+        let nack = ChunkNack {
+            app_name: app_name.to_string(),
+            missing,
+        };
+        self.pubsub.publish(&topic, &nack, self.config.qos()).await?;
+
+        Ok(())
+    }
+
     async fn wait_for_binary(&self, app_name: &str) -> Result<Vec<u8>> {
         let timeout = tokio::time::Duration::from_secs(60);
         let start = tokio::time::Instant::now();
         let polling_interval = tokio::time::Duration::from_secs(1);
+        let stall_interval = self.config.chunk_stall_interval();
+        let max_nack_rounds = self.config.max_nack_rounds;
+
+        let mut nack_rounds = 0usize;
+        let mut last_nack_sent: Option<Instant> = None;
+        let mut gave_up = false;
 
         loop {
             if start.elapsed() > timeout {
@@ -495,6 +904,34 @@ impl PropletService {
                 return Ok(binary);
             }
 
+            if !gave_up {
+                let stalled = {
+                    let assembly = self.chunk_assembly.lock().await;
+                    assembly.get(app_name).and_then(|state| {
+                        let missing = state.missing_chunks();
+                        (!missing.is_empty() && state.last_progress.elapsed() > stall_interval)
+                            .then_some(missing)
+                    })
+                };
+
+                if let Some(missing) = stalled {
+                    let due = last_nack_sent.map_or(true, |t| t.elapsed() > stall_interval);
+                    if due {
+                        if nack_rounds >= max_nack_rounds {
+                            warn!(
+                                "Giving up on NACK retries for '{}' after {} rounds; waiting out the remaining timeout",
+                                app_name, nack_rounds
+                            );
+                            gave_up = true;
+                        } else {
+                            self.publish_nack(app_name, missing).await?;
+                            nack_rounds += 1;
+                            last_nack_sent = Some(Instant::now());
+                        }
+                    }
+                }
+            }
+
             tokio::time::sleep(polling_interval).await;
         }
     }
@@ -504,7 +941,7 @@ impl PropletService {
 
         if let Some(state) = assembly.get(app_name) {
             if state.is_complete() {
-                let encrypted_binary = state.assemble();
+                let encrypted_binary = state.assemble().await?;
                 let checksum = state.checksum.clone();
 
                 info!(
@@ -513,7 +950,9 @@ impl PropletService {
                     encrypted_binary.len()
                 );
 
-                assembly.remove(app_name);
+                if let Some(state) = assembly.remove(app_name) {
+                    state.cleanup().await;
+                }
 
                 if !checksum.is_empty() {
                     let mut hasher = Sha256::new();
@@ -531,6 +970,13 @@ impl PropletService {
                 let decrypted_binary = self.decrypt_payload(&encrypted_binary)
                     .context("Failed to decrypt assembled chunks")?;
 
+                if !checksum.is_empty() {
+                    self.binary_cache
+                        .lock()
+                        .await
+                        .insert(checksum, decrypted_binary.clone());
+                }
+
                 return Ok(Some(decrypted_binary));
             }
         }
@@ -565,3 +1011,246 @@ impl PropletService {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn binary_cache_evicts_lru_past_max_entries() {
+        let mut cache = BinaryCache::new(2, 1_000);
+
+        cache.insert("a".to_string(), vec![1]);
+        std::thread::sleep(Duration::from_millis(2));
+        cache.insert("b".to_string(), vec![2]);
+        std::thread::sleep(Duration::from_millis(2));
+        cache.insert("c".to_string(), vec![3]);
+
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), Some(vec![2]));
+        assert_eq!(cache.get("c"), Some(vec![3]));
+    }
+
+    #[test]
+    fn binary_cache_evicts_enough_entries_to_fit_max_bytes() {
+        let mut cache = BinaryCache::new(10, 10);
+
+        cache.insert("a".to_string(), vec![0; 6]);
+        std::thread::sleep(Duration::from_millis(2));
+        cache.insert("b".to_string(), vec![0; 6]);
+
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), Some(vec![0; 6]));
+    }
+
+    #[test]
+    fn binary_cache_rejects_entry_larger_than_max_bytes() {
+        let mut cache = BinaryCache::new(10, 10);
+
+        cache.insert("big".to_string(), vec![0; 11]);
+
+        assert_eq!(cache.get("big"), None);
+    }
+
+    #[test]
+    fn binary_cache_get_refreshes_recency() {
+        let mut cache = BinaryCache::new(2, 1_000);
+
+        cache.insert("a".to_string(), vec![1]);
+        std::thread::sleep(Duration::from_millis(2));
+        cache.insert("b".to_string(), vec![2]);
+        std::thread::sleep(Duration::from_millis(2));
+
+        // Touch "a" so "b" becomes the least-recently-used entry.
+        assert_eq!(cache.get("a"), Some(vec![1]));
+        std::thread::sleep(Duration::from_millis(2));
+
+        cache.insert("c".to_string(), vec![3]);
+
+        assert_eq!(cache.get("a"), Some(vec![1]));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(vec![3]));
+    }
+
+    fn now_secs() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+
+    fn sign(payload: &[u8], secret: &[u8], iat: i64, exp: i64) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(payload);
+        let claims = CommandClaims {
+            body_hash: hex::encode(hasher.finalize()),
+            iat,
+            exp,
+        };
+        encode(&Header::new(jsonwebtoken::Algorithm::HS256), &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    #[test]
+    fn verify_command_token_accepts_valid_token() {
+        let secret = b"test-secret";
+        let payload = b"{\"id\":\"task-1\"}";
+        let token = sign(payload, secret, now_secs() - 1, now_secs() + 60);
+
+        assert!(verify_command_token(&token, secret, payload).is_ok());
+    }
+
+    #[test]
+    fn verify_command_token_rejects_expired_token() {
+        let secret = b"test-secret";
+        let payload = b"{\"id\":\"task-1\"}";
+        let token = sign(payload, secret, now_secs() - 120, now_secs() - 60);
+
+        assert!(verify_command_token(&token, secret, payload).is_err());
+    }
+
+    #[test]
+    fn verify_command_token_rejects_wrong_secret() {
+        let payload = b"{\"id\":\"task-1\"}";
+        let token = sign(payload, b"real-secret", now_secs() - 1, now_secs() + 60);
+
+        assert!(verify_command_token(&token, b"wrong-secret", payload).is_err());
+    }
+
+    #[test]
+    fn verify_command_token_rejects_tampered_payload() {
+        let secret = b"test-secret";
+        let signed_payload = b"{\"id\":\"task-1\"}";
+        let token = sign(signed_payload, secret, now_secs() - 1, now_secs() + 60);
+
+        let tampered_payload = b"{\"id\":\"task-2\"}";
+        assert!(verify_command_token(&token, secret, tampered_payload).is_err());
+    }
+
+    /// Builds a Merkle tree the same way `verify_merkle_path` expects:
+    /// `leaf_i = SHA256(chunk_i)`, each internal node `SHA256(left ||
+    /// right)`, duplicating the last node at odd-sized levels. Returns the
+    /// hex root and, per leaf, its authentication path.
+    fn build_merkle(chunks: &[&[u8]]) -> (String, Vec<Vec<String>>) {
+        let mut levels: Vec<Vec<Vec<u8>>> = vec![chunks
+            .iter()
+            .map(|c| {
+                let mut hasher = Sha256::new();
+                hasher.update(c);
+                hasher.finalize().to_vec()
+            })
+            .collect()];
+
+        while levels.last().unwrap().len() > 1 {
+            let cur = levels.last().unwrap();
+            let mut next = Vec::new();
+            let mut i = 0;
+            while i < cur.len() {
+                let left = &cur[i];
+                let right = cur.get(i + 1).unwrap_or(left);
+                let mut hasher = Sha256::new();
+                hasher.update(left);
+                hasher.update(right);
+                next.push(hasher.finalize().to_vec());
+                i += 2;
+            }
+            levels.push(next);
+        }
+
+        let root = hex::encode(&levels.last().unwrap()[0]);
+
+        let paths = (0..chunks.len())
+            .map(|leaf_idx| {
+                let mut idx = leaf_idx;
+                let mut path = Vec::new();
+                for level in &levels[..levels.len() - 1] {
+                    let sibling_idx = if idx % 2 == 0 {
+                        if idx + 1 < level.len() { idx + 1 } else { idx }
+                    } else {
+                        idx - 1
+                    };
+                    path.push(hex::encode(&level[sibling_idx]));
+                    idx /= 2;
+                }
+                path
+            })
+            .collect();
+
+        (root, paths)
+    }
+
+    #[test]
+    fn verify_merkle_path_accepts_every_leaf_with_odd_chunk_count() {
+        let chunks: Vec<&[u8]> = vec![b"chunk-0", b"chunk-1", b"chunk-2"];
+        let (root, paths) = build_merkle(&chunks);
+
+        for (idx, chunk) in chunks.iter().enumerate() {
+            assert!(verify_merkle_path(chunk, idx, &paths[idx], &root));
+        }
+    }
+
+    #[test]
+    fn verify_merkle_path_rejects_corrupted_chunk() {
+        let chunks: Vec<&[u8]> = vec![b"chunk-0", b"chunk-1", b"chunk-2", b"chunk-3"];
+        let (root, paths) = build_merkle(&chunks);
+
+        assert!(!verify_merkle_path(b"corrupted", 1, &paths[1], &root));
+    }
+
+    #[test]
+    fn verify_merkle_path_rejects_wrong_root() {
+        let chunks: Vec<&[u8]> = vec![b"chunk-0", b"chunk-1"];
+        let (_, paths) = build_merkle(&chunks);
+
+        assert!(!verify_merkle_path(chunks[0], 0, &paths[0], "not-the-root"));
+    }
+
+    #[test]
+    fn missing_chunks_returns_unreceived_indices() {
+        let mut state = ChunkAssemblyState::new(4, "deadbeef".to_string());
+        state.received.insert(0);
+        state.received.insert(2);
+
+        assert_eq!(state.missing_chunks(), vec![1, 3]);
+    }
+
+    #[test]
+    fn missing_chunks_is_empty_once_all_received() {
+        let mut state = ChunkAssemblyState::new(2, String::new());
+        state.received.insert(0);
+        state.received.insert(1);
+
+        assert!(state.missing_chunks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn store_chunk_ignores_chunk_redelivered_past_flush_point() {
+        let spill_dir = std::env::temp_dir();
+        let app_name = "proplet-test-redelivery-past-flush-point";
+
+        let mut state = ChunkAssemblyState::new(2, String::new());
+
+        // Spill threshold of 0 forces chunk 0 to be written straight to disk
+        // and dropped from `chunks`, advancing next_flush_idx past it.
+        state
+            .store_chunk(app_name, 0, vec![1, 2, 3], 0, &spill_dir)
+            .await
+            .unwrap();
+        assert_eq!(state.next_flush_idx, 1);
+        assert!(!state.chunks.contains_key(&0));
+
+        // A redelivery of the same index (QoS>0 MQTT resend, or a NACK
+        // racing the original chunk) must not be re-buffered.
+        state
+            .store_chunk(app_name, 0, vec![9, 9, 9], 0, &spill_dir)
+            .await
+            .unwrap();
+
+        assert_eq!(state.next_flush_idx, 1);
+        assert!(!state.chunks.contains_key(&0));
+        assert_eq!(state.received.len(), 1);
+
+        state.cleanup().await;
+    }
+}